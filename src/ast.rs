@@ -0,0 +1,572 @@
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::iter::zip;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum NodeKind {
+    Document,
+    Heading { level: u8, id: String },
+    Paragraph,
+    List { ordered: bool },
+    ListItem,
+    CodeBlock { lang: Option<String> },
+    BlockQuote,
+    TocPlaceholder,
+    Inline(String),
+}
+
+/// A single node in the document tree. Children are stored as indices into
+/// `Document::nodes` (an arena) rather than as owned `Box`es, so the tree can
+/// be built with simple index pushes/pops while walking the source lines.
+#[derive(Clone, Debug, Serialize)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+impl Node {
+    fn new(kind: NodeKind, parent: Option<usize>) -> Self {
+        Node {
+            kind,
+            parent,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// A heading collected while building the tree, used to render the table of
+/// contents. Kept flat (not nested) since nesting is derived from `level`.
+#[derive(Clone, Debug, Serialize)]
+pub struct HeadingEntry {
+    pub level: u8,
+    pub id: String,
+    pub text: String,
+}
+
+/// An arena-backed document tree, analogous to `indextree`'s flat `Vec<Node>`
+/// with parent/child indices. Node 0 is always the `Document` root.
+#[derive(Clone, Debug, Serialize)]
+pub struct Document {
+    pub nodes: Vec<Node>,
+    pub headings: Vec<HeadingEntry>,
+    pub has_toc_placeholder: bool,
+}
+
+impl Document {
+    const ROOT: usize = 0;
+
+    fn new() -> Self {
+        Document {
+            nodes: vec![Node::new(NodeKind::Document, None)],
+            headings: Vec::new(),
+            has_toc_placeholder: false,
+        }
+    }
+
+    fn push_child(&mut self, parent: usize, kind: NodeKind) -> usize {
+        let idx = self.nodes.len();
+        self.nodes.push(Node::new(kind, Some(parent)));
+        self.nodes[parent].children.push(idx);
+        idx
+    }
+
+    /// Render the collected headings as a `<ul>` nested by level, with deeper
+    /// headings nested inside the `<li>` of their nearest shallower ancestor
+    /// (as HTML5 requires — a `<ul>` may only contain `<li>` children).
+    pub fn toc_html(&self) -> String {
+        if self.headings.is_empty() {
+            return String::new();
+        }
+        let mut i = 0;
+        toc_level_html(&self.headings, &mut i, 0)
+    }
+}
+
+/// Render the run of headings starting at `*i` that are deeper than
+/// `parent_level`, recursing into a nested `<ul>` (inside the enclosing
+/// `<li>`) whenever the next heading is deeper than the current one.
+fn toc_level_html(headings: &[HeadingEntry], i: &mut usize, parent_level: u8) -> String {
+    let mut html = String::from("<ul>\n");
+    while *i < headings.len() && headings[*i].level > parent_level {
+        let heading = &headings[*i];
+        *i += 1;
+        html.push_str(&format!("<li><a href=\"#{}\">{}</a>", heading.id, heading.text));
+        if let Some(next) = headings.get(*i) {
+            if next.level > heading.level {
+                html.push('\n');
+                html.push_str(&toc_level_html(headings, i, heading.level));
+            }
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ul>\n");
+    html
+}
+
+/// Slugify heading text into an anchor id: lowercase, spaces become hyphens,
+/// punctuation is stripped, and collisions get a numeric suffix.
+fn slugify(text: &str, used: &mut HashMap<String, u32>) -> String {
+    let base: String = text
+        .chars()
+        .filter(|c| c.is_alphanumeric() || c.is_whitespace() || *c == '-')
+        .collect::<String>()
+        .trim()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-");
+    let base = if base.is_empty() {
+        "section".to_string()
+    } else {
+        base
+    };
+
+    let count = used.entry(base.clone()).or_insert(0);
+    let slug = if *count == 0 {
+        base.clone()
+    } else {
+        format!("{}-{}", base, count)
+    };
+    *count += 1;
+    slug
+}
+
+/// One level of currently-open `<ul>/<ol>` nesting while walking list items.
+struct ListLevel {
+    list_idx: usize,
+    indent: usize,
+    ordered: bool,
+    last_item_idx: usize,
+}
+
+fn indent_of(line: &str) -> usize {
+    line.chars().take_while(|c| *c == ' ').count()
+}
+
+fn heading_level(line: &str) -> Option<(u8, &str)> {
+    for level in (1..=6).rev() {
+        let prefix = "#".repeat(level);
+        if let Some(rest) = line.strip_prefix(&format!("{} ", prefix)) {
+            return Some((level as u8, rest));
+        }
+    }
+    None
+}
+
+fn list_marker(line: &str) -> Option<(bool, &str)> {
+    if let Some(rest) = line.strip_prefix("- ") {
+        return Some((false, rest));
+    }
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c.is_alphanumeric() {
+            continue;
+        } else if c == '.' && i > 0 {
+            if let Some((_, ' ')) = chars.next() {
+                return Some((true, &line[(i + 2)..]));
+            }
+            break;
+        } else {
+            break;
+        }
+    }
+    None
+}
+
+/// Apply the same inline-formatting regexes `convert` has always used
+/// (bold, italic, links, horizontal rule, inline code) to a piece of text.
+fn apply_inline(text: &str, regexes: &[Regex], htmls: &[&str]) -> String {
+    let mut text = text.to_string();
+    for (regex, html_form) in zip(regexes, htmls) {
+        text = regex.replace_all(&text, *html_form).to_string();
+    }
+    text
+}
+
+/// Build a `Document` tree out of raw source lines, determining list nesting
+/// from the count of leading spaces: a list line indented further than the
+/// open item nests one level inside it, however many spaces deeper. A
+/// literal `{{toc}}` line is only treated as a table-of-contents placeholder
+/// when `toc` is set, matching the `--toc` flag's documented behavior;
+/// otherwise it is left as ordinary paragraph text.
+pub fn build(lines: &[String], regexes: &[Regex], htmls: &[&str], toc: bool) -> Document {
+    let mut doc = Document::new();
+    let mut list_stack: Vec<ListLevel> = Vec::new();
+    let mut open_paragraph: Option<usize> = None;
+    let mut open_blockquote: Option<usize> = None;
+    let mut used_slugs: HashMap<String, u32> = HashMap::new();
+
+    let mut lines_iter = lines.iter().map(|l| l.trim_end_matches('\r')).peekable();
+
+    while let Some(line) = lines_iter.next() {
+        if let Some(lang) = line.strip_prefix("```") {
+            let lang = if lang.is_empty() {
+                None
+            } else {
+                Some(lang.to_string())
+            };
+            let mut body = String::new();
+            for code_line in lines_iter.by_ref() {
+                if code_line.trim_end_matches('\r').starts_with("```") {
+                    break;
+                }
+                if !body.is_empty() {
+                    body.push('\n');
+                }
+                body.push_str(code_line.trim_end_matches('\r'));
+            }
+            list_stack.clear();
+            open_paragraph = None;
+            open_blockquote = None;
+            let code_idx = doc.push_child(Document::ROOT, NodeKind::CodeBlock { lang });
+            doc.push_child(code_idx, NodeKind::Inline(body));
+            continue;
+        }
+
+        if line.is_empty() {
+            list_stack.clear();
+            open_paragraph = None;
+            open_blockquote = None;
+            continue;
+        }
+
+        if toc && line == "{{toc}}" {
+            list_stack.clear();
+            open_paragraph = None;
+            open_blockquote = None;
+            doc.push_child(Document::ROOT, NodeKind::TocPlaceholder);
+            doc.has_toc_placeholder = true;
+            continue;
+        }
+
+        if let Some((level, rest)) = heading_level(line) {
+            list_stack.clear();
+            open_paragraph = None;
+            open_blockquote = None;
+            let text = apply_inline(rest, regexes, htmls);
+            let id = slugify(rest, &mut used_slugs);
+            doc.headings.push(HeadingEntry {
+                level,
+                id: id.clone(),
+                text: text.clone(),
+            });
+            let heading_idx = doc.push_child(Document::ROOT, NodeKind::Heading { level, id });
+            doc.push_child(heading_idx, NodeKind::Inline(text));
+            continue;
+        }
+
+        let indent = indent_of(line);
+
+        // Indented deeper than the currently open list item's marker: this
+        // line is nested inside that item (a blockquote or plain
+        // continuation text), not a sibling that should close the list.
+        let list_item_idx = list_stack
+            .last()
+            .filter(|level| indent > level.indent)
+            .map(|level| level.last_item_idx);
+
+        if let Some(rest) = line[indent..].strip_prefix("> ") {
+            open_paragraph = None;
+            let parent = match list_item_idx {
+                Some(item_idx) => item_idx,
+                None => {
+                    list_stack.clear();
+                    Document::ROOT
+                }
+            };
+            let quote_idx = *open_blockquote.get_or_insert_with(|| {
+                doc.push_child(parent, NodeKind::BlockQuote)
+            });
+            doc.push_child(quote_idx, NodeKind::Inline(apply_inline(rest, regexes, htmls)));
+            continue;
+        }
+        open_blockquote = None;
+
+        if let Some((ordered, rest)) = list_marker(&line[indent..]) {
+            open_paragraph = None;
+
+            // Dedent past shallower levels, and also drop the current level
+            // if the marker type changed (e.g. `- ` to `1. `) at the same
+            // indent, so that switch starts a new sibling list.
+            while list_stack.last().is_some_and(|level| {
+                indent < level.indent || (indent == level.indent && level.ordered != ordered)
+            }) {
+                list_stack.pop();
+            }
+
+            let needs_new_list = match list_stack.last() {
+                Some(level) => indent > level.indent,
+                None => true,
+            };
+
+            if needs_new_list {
+                let parent = match list_stack.last() {
+                    Some(level) => level.last_item_idx,
+                    None => Document::ROOT,
+                };
+                let list_idx = doc.push_child(parent, NodeKind::List { ordered });
+                list_stack.push(ListLevel {
+                    list_idx,
+                    indent,
+                    ordered,
+                    last_item_idx: list_idx,
+                });
+            }
+
+            let level = list_stack.last_mut().unwrap();
+            let item_idx = doc.push_child(level.list_idx, NodeKind::ListItem);
+            doc.push_child(item_idx, NodeKind::Inline(apply_inline(rest, regexes, htmls)));
+            level.last_item_idx = item_idx;
+            continue;
+        }
+
+        // Plain continuation text nested under an open list item: keep it
+        // (and the list) rather than falling through to the paragraph
+        // branch below, which would clear `list_stack` and split the list.
+        if let Some(item_idx) = list_item_idx {
+            open_paragraph = None;
+            doc.push_child(
+                item_idx,
+                NodeKind::Inline(apply_inline(line[indent..].trim_start(), regexes, htmls)),
+            );
+            continue;
+        }
+        list_stack.clear();
+
+        let formatted = apply_inline(line, regexes, htmls);
+        let paragraph_idx = *open_paragraph.get_or_insert_with(|| {
+            doc.push_child(Document::ROOT, NodeKind::Paragraph)
+        });
+        doc.push_child(paragraph_idx, NodeKind::Inline(formatted));
+    }
+
+    doc
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+static SYNTAX_SET: std::sync::OnceLock<syntect::parsing::SyntaxSet> = std::sync::OnceLock::new();
+static THEME_SET: std::sync::OnceLock<syntect::highlighting::ThemeSet> = std::sync::OnceLock::new();
+
+/// Tokenize `code` with `syntect` using the declared fence language and
+/// render it as colored `<span>`s, so highlighting works without
+/// client-side JavaScript. Falls back to plain HTML-escaped text when the
+/// language is unset or unknown to syntect.
+fn highlight_code(lang: Option<&str>, code: &str) -> String {
+    use syntect::easy::HighlightLines;
+    use syntect::html::{IncludeBackground, styled_line_to_highlighted_html};
+    use syntect::util::LinesWithEndings;
+
+    let syntax_set = SYNTAX_SET.get_or_init(syntect::parsing::SyntaxSet::load_defaults_newlines);
+    let theme_set = THEME_SET.get_or_init(syntect::highlighting::ThemeSet::load_defaults);
+
+    let syntax = lang.and_then(|lang| syntax_set.find_syntax_by_token(lang));
+    let Some(syntax) = syntax else {
+        return html_escape(code);
+    };
+
+    let theme = &theme_set.themes["InspiredGitHub"];
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut html = String::new();
+    for line in LinesWithEndings::from(code) {
+        let Ok(ranges) = highlighter.highlight_line(line, syntax_set) else {
+            return html_escape(code);
+        };
+        let Ok(rendered) = styled_line_to_highlighted_html(&ranges[..], IncludeBackground::No)
+        else {
+            return html_escape(code);
+        };
+        html.push_str(&rendered);
+    }
+    html
+}
+
+/// Render the document tree to HTML with a single recursive tree-walk, so
+/// new block types only need a new `NodeKind` variant plus a match arm here.
+pub fn render_html(doc: &Document) -> String {
+    render_node(doc, Document::ROOT)
+}
+
+fn render_node(doc: &Document, idx: usize) -> String {
+    let node = &doc.nodes[idx];
+    let inner = || -> String {
+        node.children
+            .iter()
+            .map(|&child| render_node(doc, child))
+            .collect::<Vec<_>>()
+            .join("")
+    };
+
+    match &node.kind {
+        NodeKind::Document => {
+            let mut text = String::new();
+            for &child in &node.children {
+                text.push_str(&render_node(doc, child));
+                text.push('\n');
+            }
+            text
+        }
+        NodeKind::Heading { level, id } => {
+            format!("<h{0} id=\"{1}\">{2}</h{0}>", level, id, inner())
+        }
+        NodeKind::Paragraph => {
+            let body = node
+                .children
+                .iter()
+                .map(|&child| render_node(doc, child))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("<p>{}</p>", body)
+        }
+        NodeKind::List { ordered } => {
+            let tag = if *ordered { "ol" } else { "ul" };
+            format!("<{0}>\n{1}</{0}>", tag, inner())
+        }
+        NodeKind::ListItem => {
+            // Join multi-part item content (e.g. a continuation line or a
+            // nested blockquote) with a space, like `Paragraph`/`BlockQuote`
+            // do, but don't inject one before a nested sub-list.
+            let mut body = String::new();
+            for (i, &child) in node.children.iter().enumerate() {
+                if i > 0 && !matches!(doc.nodes[child].kind, NodeKind::List { .. }) {
+                    body.push(' ');
+                }
+                body.push_str(&render_node(doc, child));
+            }
+            format!("<li>{}</li>\n", body)
+        }
+        NodeKind::CodeBlock { lang } => {
+            let class = match lang {
+                Some(lang) => format!(" class=\"language-{}\"", lang),
+                None => String::new(),
+            };
+            let raw = node
+                .children
+                .iter()
+                .map(|&child| match &doc.nodes[child].kind {
+                    NodeKind::Inline(text) => text.as_str(),
+                    _ => "",
+                })
+                .collect::<String>();
+            let body = highlight_code(lang.as_deref(), &raw);
+            format!("<pre><code{}>{}</code></pre>", class, body)
+        }
+        NodeKind::BlockQuote => {
+            let body = node
+                .children
+                .iter()
+                .map(|&child| render_node(doc, child))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("<blockquote>\n{}</blockquote>", body)
+        }
+        NodeKind::TocPlaceholder => doc.toc_html(),
+        NodeKind::Inline(text) => text.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(markdown: &[&str]) -> String {
+        let lines: Vec<String> = markdown.iter().map(|s| s.to_string()).collect();
+        let doc = build(&lines, &[], &[], false);
+        render_html(&doc)
+    }
+
+    #[test]
+    fn nested_unordered_list() {
+        let html = render(&["- top", "  - nested", "- top2"]);
+        assert_eq!(
+            html.trim(),
+            "<ul>\n<li>top<ul>\n<li>nested</li>\n</ul></li>\n<li>top2</li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn marker_type_change_starts_new_list() {
+        let html = render(&["- a", "- b", "1. c", "2. d"]);
+        assert!(html.contains("<ol>\n<li>c</li>\n<li>d</li>\n</ol>"));
+        assert!(html.contains("<ul>\n<li>a</li>\n<li>b</li>\n</ul>"));
+    }
+
+    #[test]
+    fn multiline_blockquote_joins_with_space() {
+        let html = render(&["> Hello", "> world"]);
+        assert_eq!(html.trim(), "<blockquote>\nHello world</blockquote>");
+    }
+
+    #[test]
+    fn blockquote_nested_inside_list_item() {
+        let html = render(&["- item", "  > a quote"]);
+        assert_eq!(
+            html.trim(),
+            "<ul>\n<li>item <blockquote>\na quote</blockquote></li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn continuation_text_stays_in_list_item() {
+        let html = render(&["- item one", "  continuation text", "- item two"]);
+        assert_eq!(
+            html.trim(),
+            "<ul>\n<li>item one continuation text</li>\n<li>item two</li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn toc_nests_ul_inside_li() {
+        let lines: Vec<String> = ["# A", "## A1", "## A2", "# B"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let doc = build(&lines, &[], &[], false);
+        let toc = doc.toc_html();
+        assert_eq!(
+            toc.trim(),
+            "<ul>\n<li><a href=\"#a\">A</a>\n<ul>\n<li><a href=\"#a1\">A1</a></li>\n<li><a href=\"#a2\">A2</a></li>\n</ul>\n</li>\n<li><a href=\"#b\">B</a></li>\n</ul>"
+        );
+    }
+
+    #[test]
+    fn highlight_code_escapes_html_for_unknown_language() {
+        let html = highlight_code(Some("not-a-real-language"), "<a & b>");
+        assert_eq!(html, "&lt;a &amp; b&gt;");
+    }
+
+    #[test]
+    fn highlight_code_escapes_html_when_no_language() {
+        let html = highlight_code(None, "<a & b>");
+        assert_eq!(html, "&lt;a &amp; b&gt;");
+    }
+
+    #[test]
+    fn code_block_emits_language_class_for_known_language() {
+        let lines: Vec<String> = ["```rust", "let x = 1;", "```"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let doc = build(&lines, &[], &[], false);
+        let html = render_html(&doc);
+        assert!(html.contains("<pre><code class=\"language-rust\">"));
+    }
+
+    #[test]
+    fn toc_placeholder_only_substituted_when_toc_flag_is_set() {
+        let lines: Vec<String> = vec!["{{toc}}".to_string()];
+
+        let without_flag = build(&lines, &[], &[], false);
+        assert!(!without_flag.has_toc_placeholder);
+        assert_eq!(render_html(&without_flag).trim(), "<p>{{toc}}</p>");
+
+        let with_flag = build(&lines, &[], &[], true);
+        assert!(with_flag.has_toc_placeholder);
+    }
+}
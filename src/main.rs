@@ -1,10 +1,11 @@
-use clap::Parser;
-use notify::{RecursiveMode, Watcher, recommended_watcher};
+mod ast;
+mod watch;
+
+use clap::{Parser, ValueEnum};
 use regex::Regex;
 use std::fs;
 use std::io::Read;
-use std::iter::zip;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
@@ -19,12 +20,10 @@ const LIVE_RELOAD_SCRIPT: &str = r#"
 </script>
 "#;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum State {
-    Normal,
-    OrderedList,
-    UnorderedList,
-    Code,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Html,
+    Json,
 }
 
 #[derive(Parser)]
@@ -36,9 +35,15 @@ struct Args {
     watch: bool,
     #[arg(short, long)]
     config: Option<String>,
+    #[arg(short, long, value_enum, default_value_t = Format::Html)]
+    format: Format,
+    /// Insert a table of contents at `{{toc}}`, or at the top of the body if
+    /// no placeholder is present.
+    #[arg(long)]
+    toc: bool,
 }
 
-fn parse_file(path: &str) -> std::io::Result<Vec<String>> {
+fn parse_file(path: &Path) -> std::io::Result<Vec<String>> {
     let mut file = fs::File::open(path)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
@@ -58,163 +63,6 @@ fn parse_file(path: &str) -> std::io::Result<Vec<String>> {
     Ok(lines)
 }
 
-fn parse_headings(line: &str) -> String {
-    let line = line.trim_end_matches('\r');
-
-    // Check for heading
-    for i in (1..=6).rev() {
-        let mut identifier = String::new();
-        for _ in 1..=i {
-            identifier.push('#');
-        }
-        identifier.push(' ');
-        if line.starts_with(&identifier) {
-            return format!("<h{0}>{1}</h{0}>", i, &line[(i + 1)..]);
-        }
-    }
-    return line.to_string();
-}
-
-fn parse_lists(line: &str) -> String {
-    let mut line = line.trim_end_matches('\r').to_string();
-
-    if line.starts_with("- ") {
-        line = format!("<li>{}</li>", &line[2..]);
-    };
-
-    let mut chars = line.chars().enumerate();
-    while let Some((i, c)) = chars.next() {
-        if c.is_alphanumeric() {
-            continue;
-        } else if c == '.' && i > 0 {
-            if let Some((_, ' ')) = chars.next() {
-                line = format!("<li>{}</li>", &line[(i + 2)..]);
-            }
-            break;
-        } else {
-            break;
-        }
-    }
-    line.to_string()
-}
-
-fn parse_line(line: &str, regexes: &[Regex], htmls: &[&str]) -> String {
-    let line = parse_headings(line);
-    let mut line = parse_lists(&line);
-
-    for (regex, html_form) in zip(regexes, htmls) {
-        line = regex.replace_all(&line, *html_form).to_string();
-    }
-    if line.starts_with("```") {
-        line = String::new();
-    }
-    line
-}
-
-fn classify_lines(lines: &Vec<String>) -> Vec<State> {
-    let mut states = Vec::new();
-    states.resize(lines.len(), State::Normal);
-    for (i, line) in lines.iter().enumerate() {
-        if line.starts_with("- ") {
-            states[i] = State::UnorderedList;
-        };
-    }
-
-    for (line_index, line) in lines.iter().enumerate() {
-        let mut chars = line.chars().enumerate();
-        while let Some((i, c)) = chars.next() {
-            if c.is_alphanumeric() {
-                continue;
-            } else if c == '.' && i > 0 {
-                if let Some((_, ' ')) = chars.next() {
-                    states[line_index] = State::OrderedList;
-                }
-                break;
-            } else {
-                break;
-            }
-        }
-    }
-
-    let mut inside_code_block: bool = false;
-    for (line_index, line) in lines.iter().enumerate() {
-        if line.starts_with("```") {
-            if inside_code_block {
-                inside_code_block = !inside_code_block;
-                continue;
-            };
-            inside_code_block = !inside_code_block;
-        };
-
-        if inside_code_block {
-            states[line_index] = State::Code;
-        }
-    }
-    states
-}
-
-fn convert_lines(lines: &Vec<String>, states: &Vec<State>) -> String {
-    let mut text = String::new();
-
-    let mut previous_state = State::Normal;
-    let mut in_paragraph = false;
-    for (i, line) in lines.iter().enumerate() {
-        let mut line = line.clone();
-        let state = states[i];
-        let is_plain = state == State::Normal && !line.is_empty() && !line.starts_with('<');
-
-        if is_plain {
-            if !in_paragraph {
-                text.push_str("<p>");
-                in_paragraph = true;
-            } else {
-                text.push(' ');
-            }
-            text.push_str(&line);
-            continue;
-        } else if in_paragraph {
-            text.push_str("</p>\n");
-            in_paragraph = false;
-        }
-
-        if previous_state != state {
-            match state {
-                State::OrderedList => {
-                    line = format!("<ol>\n{}", line);
-                }
-                State::UnorderedList => {
-                    line = format!("<ul>\n{}", line);
-                }
-                State::Code => {
-                    line = format!("<pre><code>");
-                }
-                State::Normal => match previous_state {
-                    State::OrderedList => {
-                        line = format!("{}\n</ol>", line);
-                    }
-                    State::UnorderedList => {
-                        line = format!("{}\n</ul>", line);
-                    }
-                    State::Code => {
-                        line = format!("</code></pre>");
-                    }
-                    State::Normal => {}
-                },
-            }
-        };
-        previous_state = state;
-
-        text.push_str(&line);
-        text.push('\n');
-    }
-
-    if in_paragraph {
-        text.push_str("</p>");
-    };
-
-    text
-}
-
 fn wrap_html(body: &str, title: &str, style: &str) -> String {
     format!(
         "<!DOCTYPE html><head><style>{}</style><title>{}</title></head><body>{}{}</body>",
@@ -222,8 +70,8 @@ fn wrap_html(body: &str, title: &str, style: &str) -> String {
     )
 }
 
-fn convert(input_path: &String, output_path: &String, style:&str) {
-    let mut file_lines = parse_file(input_path).expect("Error during file parsing");
+fn convert(input_path: &Path, output_path: &Path, style: &str, format: Format, toc: bool) {
+    let file_lines = parse_file(input_path).expect("Error during file parsing");
 
     let regexes = [
         Regex::new(r"\*\*(.*?)\*\*").unwrap(),
@@ -240,25 +88,91 @@ fn convert(input_path: &String, output_path: &String, style:&str) {
         "<code>$1</code>",
     ];
 
-    let line_states = classify_lines(&file_lines);
-    for (i, line) in &mut file_lines.iter_mut().enumerate() {
-        if line_states[i] != State::Code {
-            *line = parse_line(line, &regexes, &htmls);
+    let document = ast::build(&file_lines, &regexes, &htmls, toc);
+
+    let text = match format {
+        Format::Html => {
+            let mut body = ast::render_html(&document);
+            if toc && !document.has_toc_placeholder {
+                body = format!("{}{}", document.toc_html(), body);
+            }
+            wrap_html(&body, &input_path.to_string_lossy(), style)
         }
-    }
-    let text = convert_lines(&file_lines, &line_states);
-    let text = wrap_html(&text, input_path, style);
+        Format::Json => {
+            serde_json::to_string_pretty(&document).expect("Error serializing document to JSON")
+        }
+    };
     fs::write(output_path, &text).expect("Error writing to the file");
-    //println!("{}", &text);
 }
 
-fn start_server(output_path: String, reload_flag: Arc<AtomicBool>) {
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html",
+        Some("css") => "text/css",
+        Some("js") => "application/javascript",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Resolve a request URL to a file under `root`, rejecting any path that
+/// escapes it (e.g. via `..`).
+fn resolve_served_path(root: &Path, url: &str) -> Option<PathBuf> {
+    let url_path = url.split('?').next().unwrap_or(url);
+    let relative = url_path.trim_start_matches('/');
+    let candidate = if relative.is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(relative)
+    };
+
+    let root = root.canonicalize().ok()?;
+    let resolved = candidate.canonicalize().ok()?;
+    if resolved.starts_with(&root) {
+        Some(resolved)
+    } else {
+        None
+    }
+}
+
+fn render_directory_index(root: &Path, dir: &Path, url: &str) -> String {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .map(|read_dir| read_dir.flatten().collect())
+        .unwrap_or_default();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    let mut list = String::new();
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let suffix = if entry.path().is_dir() { "/" } else { "" };
+        list.push_str(&format!(
+            "<li><a href=\"{}/{}\">{}{}</a></li>\n",
+            url.trim_end_matches('/'),
+            name,
+            name,
+            suffix
+        ));
+    }
+
+    format!(
+        "<!DOCTYPE html><title>Index of {}</title><h1>Index of {}</h1><ul>\n{}</ul>",
+        dir.strip_prefix(root).unwrap_or(dir).display(),
+        url,
+        list
+    )
+}
+
+fn start_server(root: PathBuf, reload_flag: Arc<AtomicBool>) {
     let server = Arc::new(Server::http("0.0.0.0:8080").expect("Failed to start server"));
     println!("Serving on http://localhost:8080");
 
     for request in server.incoming_requests() {
         let reload_flag = reload_flag.clone();
-        let output_path = output_path.clone();
+        let root = root.clone();
 
         thread::spawn(move || {
             let url = request.url().to_string();
@@ -276,31 +190,81 @@ fn start_server(output_path: String, reload_flag: Arc<AtomicBool>) {
                     }
                     thread::sleep(std::time::Duration::from_millis(100));
                 }
-            } else {
-                let html = fs::read_to_string(&output_path)
-                    .unwrap_or_else(|_| "<p>File not found</p>".to_string());
-                let _ = request.respond(
-                    Response::from_string(html)
-                        .with_header("Content-Type: text/html".parse::<Header>().unwrap()),
-                );
+                return;
+            }
+
+            match resolve_served_path(&root, &url) {
+                Some(path) if path.is_dir() => {
+                    let index = path.join("index.html");
+                    let body = if index.is_file() {
+                        fs::read_to_string(&index).unwrap_or_default()
+                    } else {
+                        render_directory_index(&root, &path, &url)
+                    };
+                    let _ = request.respond(
+                        Response::from_string(body)
+                            .with_header("Content-Type: text/html".parse::<Header>().unwrap()),
+                    );
+                }
+                Some(path) => {
+                    let body = fs::read(&path).unwrap_or_default();
+                    let content_type = content_type(&path);
+                    let _ = request.respond(
+                        Response::from_data(body).with_header(
+                            format!("Content-Type: {}", content_type)
+                                .parse::<Header>()
+                                .unwrap(),
+                        ),
+                    );
+                }
+                None => {
+                    let _ = request.respond(
+                        Response::from_string("<p>File not found</p>")
+                            .with_status_code(404)
+                            .with_header("Content-Type: text/html".parse::<Header>().unwrap()),
+                    );
+                }
             }
         });
     }
 }
 
 // In your main function, replace open::that() with this:
-fn start_live_server(output_path: &str) -> Arc<AtomicBool> {
+fn start_live_server(root: PathBuf) -> Arc<AtomicBool> {
     let reload_flag = Arc::new(AtomicBool::new(false));
     let reload_flag_clone = reload_flag.clone();
-    let output_path = output_path.to_string();
 
     thread::spawn(move || {
-        start_server(output_path, reload_flag_clone);
+        start_server(root, reload_flag_clone);
     });
 
     reload_flag
 }
 
+/// The directory the live-preview server should serve for a given `output`
+/// path: `output` itself if it's already a directory, otherwise its parent
+/// (falling back to `.` for a bare relative filename with no parent
+/// component, since `Path::parent` returns `Some("")` rather than `None`
+/// there).
+fn served_root_for(output: &Path) -> PathBuf {
+    if output.is_dir() {
+        output.to_path_buf()
+    } else {
+        output
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."))
+            .to_path_buf()
+    }
+}
+
+fn format_extension(format: Format) -> &'static str {
+    match format {
+        Format::Html => "html",
+        Format::Json => "json",
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
@@ -309,29 +273,83 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         None => include_str!("style.css").to_string()
     };
 
-    convert(&args.input, &args.output, &style);
+    let input = Path::new(&args.input).to_path_buf();
+    let output = Path::new(&args.output).to_path_buf();
+    let format = args.format;
+    let toc = args.toc;
+    let ext = format_extension(format);
+
+    watch::convert_tree(&input, &output, ext, |input_path, output_path| {
+        convert(input_path, output_path, &style, format, toc);
+    });
 
     if !args.watch {
         return Ok(())
     };
-    let input = args.input.clone();
-    let output = args.output.clone();
 
-    let reload_flag = start_live_server(&args.output);
+    let reload_flag = start_live_server(served_root_for(&output));
 
-    let reload_flag_clone = reload_flag.clone();
-    let mut watcher = recommended_watcher(move |res| match res {
-        Ok(_) => {
-            println!("File changed, reconverting...");
-            convert(&input, &output, &style);
-            reload_flag_clone.store(true, Ordering::Relaxed);
-        }
-        Err(e) => eprintln!("Watch error {:?}", e),
+    watch::watch_and_convert(input, output, ext.to_string(), reload_flag, move |input_path, output_path| {
+        convert(input_path, output_path, &style, format, toc);
     })?;
 
-    watcher.watch(Path::new(&args.input), RecursiveMode::NonRecursive)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A throwaway directory under the OS temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("md_to_html_test_{}", name));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn served_root_for_bare_filename_falls_back_to_cwd() {
+        assert_eq!(served_root_for(Path::new("output.html")), Path::new("."));
+    }
+
+    #[test]
+    fn served_root_for_nested_file_uses_parent() {
+        assert_eq!(
+            served_root_for(Path::new("site/output.html")),
+            Path::new("site")
+        );
+    }
+
+    #[test]
+    fn resolve_served_path_rejects_traversal() {
+        let dir = TempDir::new("traversal");
+        fs::write(dir.0.join("page.html"), "hi").unwrap();
+
+        assert!(resolve_served_path(&dir.0, "/page.html").is_some());
+        assert!(resolve_served_path(&dir.0, "/../page.html").is_none());
+        assert!(resolve_served_path(&dir.0, "/does-not-exist.html").is_none());
+    }
+
+    #[test]
+    fn directory_index_href_has_no_duplicated_or_missing_slash() {
+        let dir = TempDir::new("index");
+        fs::write(dir.0.join("file.txt"), "hi").unwrap();
+
+        let with_trailing_slash = render_directory_index(&dir.0, &dir.0, "/sub/");
+        let without_trailing_slash = render_directory_index(&dir.0, &dir.0, "/sub");
 
-    loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        assert!(with_trailing_slash.contains("href=\"/sub/file.txt\""));
+        assert!(without_trailing_slash.contains("href=\"/sub/file.txt\""));
     }
 }
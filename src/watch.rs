@@ -0,0 +1,211 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::Duration;
+
+use notify::{Event, RecursiveMode, Watcher, recommended_watcher};
+
+/// How long to wait for the filesystem to go quiet before reconverting a
+/// batch of changed paths. A single save can emit several raw events (write,
+/// rename, metadata), so we coalesce anything inside this window.
+const DEBOUNCE_WINDOW: Duration = Duration::from_millis(200);
+
+/// Recursively collect every `*.md` file under `root` (or just `root` itself
+/// if it is already a markdown file).
+pub fn collect_markdown_files(root: &Path) -> Vec<PathBuf> {
+    if root.is_file() && root.extension().is_some_and(|ext| ext == "md") {
+        return vec![root.to_path_buf()];
+    }
+    let mut files = Vec::new();
+    collect_markdown_files_into(root, &mut files);
+    files
+}
+
+fn collect_markdown_files_into(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_markdown_files_into(&path, files);
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            files.push(path);
+        }
+    }
+}
+
+/// Map a markdown file under `input_root` to its mirrored path under
+/// `output_root`, preserving subfolders and swapping the extension.
+pub fn mirrored_output_path(input_root: &Path, output_root: &Path, md_path: &Path, ext: &str) -> PathBuf {
+    let relative = md_path.strip_prefix(input_root).unwrap_or(md_path);
+    output_root.join(relative).with_extension(ext)
+}
+
+/// Convert every markdown file found under `input` into `output`, mirroring
+/// the directory structure. `input` may also be a single file, in which case
+/// `output` is treated as the exact destination path.
+pub fn convert_tree(input: &Path, output: &Path, ext: &str, mut convert_one: impl FnMut(&Path, &Path)) {
+    if input.is_dir() {
+        for md_path in collect_markdown_files(input) {
+            let out_path = mirrored_output_path(input, output, &md_path, ext);
+            if let Some(parent) = out_path.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            convert_one(&md_path, &out_path);
+        }
+    } else {
+        convert_one(input, output);
+    }
+}
+
+/// From the markdown files currently on disk, pick the ones affected by this
+/// batch of raw changed paths, so a renamed or newly created file is picked
+/// up while untouched files are left alone.
+fn files_to_reconvert(all_markdown: &[PathBuf], changed: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    all_markdown
+        .iter()
+        .filter(|md_path| changed.iter().any(|p| p == *md_path || p.starts_with(md_path)))
+        .cloned()
+        .collect()
+}
+
+/// Watch `input` (recursively, if it is a directory) and reconvert only the
+/// markdown files that actually changed once the filesystem goes quiet for
+/// `DEBOUNCE_WINDOW`. Events under `output` are ignored so writing the
+/// generated files never re-triggers the watcher.
+pub fn watch_and_convert(
+    input: PathBuf,
+    output: PathBuf,
+    ext: String,
+    reload_flag: Arc<AtomicBool>,
+    mut convert_one: impl FnMut(&Path, &Path) + Send + 'static,
+) -> notify::Result<()> {
+    let (tx, rx) = mpsc::channel::<PathBuf>();
+    let output_for_filter = output.clone();
+
+    let mut watcher = recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => {
+            for path in event.paths {
+                if path.starts_with(&output_for_filter) {
+                    continue;
+                }
+                let _ = tx.send(path);
+            }
+        }
+        Err(e) => eprintln!("Watch error {:?}", e),
+    })?;
+
+    let recursive_mode = if input.is_dir() {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    watcher.watch(&input, recursive_mode)?;
+
+    let is_dir = input.is_dir();
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    loop {
+        match rx.recv_timeout(DEBOUNCE_WINDOW) {
+            Ok(path) => {
+                pending.insert(path);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if pending.is_empty() {
+                    continue;
+                }
+                let changed = std::mem::take(&mut pending);
+                println!("File(s) changed, reconverting...");
+
+                if is_dir {
+                    // Recompute the live set of markdown files so renamed or
+                    // newly created files are picked up, then reconvert only
+                    // the ones that were part of this change batch.
+                    for md_path in files_to_reconvert(&collect_markdown_files(&input), &changed) {
+                        let out_path = mirrored_output_path(&input, &output, &md_path, &ext);
+                        if let Some(parent) = out_path.parent() {
+                            let _ = std::fs::create_dir_all(parent);
+                        }
+                        convert_one(&md_path, &out_path);
+                    }
+                } else {
+                    convert_one(&input, &output);
+                }
+
+                reload_flag.store(true, Ordering::Relaxed);
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A throwaway directory under the OS temp dir, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("md_to_html_watch_test_{}", name));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn collect_markdown_files_finds_nested_md_only() {
+        let dir = TempDir::new("collect");
+        std::fs::write(dir.0.join("a.md"), "hi").unwrap();
+        std::fs::write(dir.0.join("notes.txt"), "hi").unwrap();
+        std::fs::create_dir_all(dir.0.join("sub")).unwrap();
+        std::fs::write(dir.0.join("sub").join("b.md"), "hi").unwrap();
+
+        let mut found = collect_markdown_files(&dir.0);
+        found.sort();
+
+        let mut expected = vec![dir.0.join("a.md"), dir.0.join("sub").join("b.md")];
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn mirrored_output_path_preserves_subfolders_and_swaps_extension() {
+        let input_root = Path::new("site");
+        let output_root = Path::new("dist");
+        let md_path = Path::new("site/posts/hello.md");
+
+        assert_eq!(
+            mirrored_output_path(input_root, output_root, md_path, "html"),
+            Path::new("dist/posts/hello.html")
+        );
+    }
+
+    #[test]
+    fn files_to_reconvert_only_picks_changed_files() {
+        let all = vec![Path::new("a.md").to_path_buf(), Path::new("b.md").to_path_buf()];
+        let changed: HashSet<PathBuf> = [Path::new("b.md").to_path_buf()].into_iter().collect();
+
+        assert_eq!(files_to_reconvert(&all, &changed), vec![Path::new("b.md").to_path_buf()]);
+    }
+
+    #[test]
+    fn files_to_reconvert_ignores_untouched_files() {
+        let all = vec![Path::new("a.md").to_path_buf()];
+        let changed: HashSet<PathBuf> = [Path::new("b.md").to_path_buf()].into_iter().collect();
+
+        assert!(files_to_reconvert(&all, &changed).is_empty());
+    }
+}